@@ -1,80 +1,407 @@
 use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use log::info;
+use rand::RngCore;
 use serde_json::Value;
 use std::path::PathBuf;
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-/// Get OpenCode data directory path (~/.local/share/opencode)
-fn get_data_dir() -> PathBuf {
-    dirs::home_dir()
-        .expect("Cannot determine home directory")
-        .join(".local")
-        .join("share")
-        .join("opencode")
+/// Magic header identifying an encrypted auth container.
+const CRYPT_MAGIC: &[u8; 8] = b"OCCRYPT1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+/// Whether `auth.json` is stored as plaintext JSON or encrypted at rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptMode {
+    None,
+    Encrypt,
+}
+
+impl CryptMode {
+    /// Resolve an explicitly-configured crypt-mode from the environment.
+    /// Returns `None` when the env var is unset, distinct from a `Some`
+    /// result, so callers can tell "not configured" apart from "configured
+    /// off" — `write_auth_in` uses that distinction to let a user who sets
+    /// `OPENCHAMBER_CRYPT_MODE=none` explicitly flip an already-encrypted
+    /// `auth.json` back to plaintext, rather than ratcheting one-way.
+    fn current() -> Option<Self> {
+        match std::env::var("OPENCHAMBER_CRYPT_MODE")
+            .or_else(|_| std::env::var("OPENCODE_CRYPT_MODE"))
+        {
+            Ok(value) if value.eq_ignore_ascii_case("encrypt") => Some(CryptMode::Encrypt),
+            Ok(value)
+                if value.eq_ignore_ascii_case("none") || value.eq_ignore_ascii_case("decrypt") =>
+            {
+                Some(CryptMode::None)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Get the auth passphrase from an env var, or prompt on the TTY (on a
+/// blocking thread) as a fallback.
+async fn auth_passphrase() -> Result<String> {
+    if let Ok(passphrase) = std::env::var("OPENCHAMBER_AUTH_PASSPHRASE")
+        .or_else(|_| std::env::var("OPENCODE_AUTH_PASSPHRASE"))
+    {
+        return Ok(passphrase);
+    }
+
+    tokio::task::spawn_blocking(|| {
+        rpassword::prompt_password("Auth passphrase: ")
+            .map_err(|e| anyhow!("Failed to read passphrase: {}", e))
+    })
+    .await
+    .map_err(|e| anyhow!("Passphrase prompt task panicked: {}", e))?
+}
+
+/// Derive a 256-bit key from `passphrase` and `salt` using Argon2id.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive key from passphrase: {}", e))?;
+    Ok(key)
+}
+
+/// Encrypt `plaintext` into the container format: magic header, salt,
+/// nonce, then ciphertext.
+fn encrypt_auth(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new((&key).into());
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = XNonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext)
+        .map_err(|e| anyhow!("Failed to encrypt auth file: {}", e))?;
+
+    let mut out = Vec::with_capacity(CRYPT_MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(CRYPT_MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce_bytes);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypt a container produced by [`encrypt_auth`]. A tag mismatch
+/// surfaces as a distinct error from a JSON parse failure.
+fn decrypt_auth(container: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let rest = &container[CRYPT_MAGIC.len()..];
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        return Err(anyhow!("Auth file is encrypted but truncated"));
+    }
+
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce_bytes, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let key = derive_key(passphrase, salt)?;
+    let cipher = XChaCha20Poly1305::new((&key).into());
+    let nonce = XNonce::from_slice(nonce_bytes);
+
+    cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|_| anyhow!("Wrong passphrase or tampered auth file"))
+}
+
+/// Whether the file at `path` is an existing encrypted auth container,
+/// so `write_auth_in` can preserve encryption on an existing file instead
+/// of relying solely on the current `CryptMode` env var.
+async fn file_is_encrypted(path: &std::path::Path) -> Result<bool> {
+    let mut file = match fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut magic = [0u8; CRYPT_MAGIC.len()];
+    match file.read_exact(&mut magic).await {
+        Ok(_) => Ok(&magic == CRYPT_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The resolved OpenCode data directory.
+#[derive(Debug, Clone)]
+pub struct DataDir(PathBuf);
+
+impl DataDir {
+    /// Resolve the data directory: `OPENCHAMBER_DATA_DIR`/`OPENCODE_DATA_DIR`,
+    /// then `XDG_DATA_HOME`, then `~/.local/share`, with `opencode` appended.
+    pub fn resolve() -> Self {
+        let base = std::env::var_os("OPENCHAMBER_DATA_DIR")
+            .or_else(|| std::env::var_os("OPENCODE_DATA_DIR"))
+            .or_else(|| std::env::var_os("XDG_DATA_HOME"))
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                dirs::home_dir()
+                    .expect("Cannot determine home directory")
+                    .join(".local")
+                    .join("share")
+            });
+
+        Self(base.join("opencode"))
+    }
+
+    /// Point directly at an arbitrary directory, bypassing env resolution.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
 }
 
-/// Get auth file path
-fn get_auth_file() -> PathBuf {
-    get_data_dir().join("auth.json")
+/// Get auth file path within `dir`
+fn get_auth_file(dir: &DataDir) -> PathBuf {
+    dir.path().join("auth.json")
 }
 
-/// Ensure data directory exists
-async fn ensure_data_dir() -> Result<()> {
-    let data_dir = get_data_dir();
-    fs::create_dir_all(&data_dir).await?;
+/// Ensure `dir` exists
+async fn ensure_data_dir(dir: &DataDir) -> Result<()> {
+    fs::create_dir_all(dir.path()).await?;
     Ok(())
 }
 
-/// Read auth.json file
+/// Read auth.json file from the resolved default data directory.
 pub async fn read_auth() -> Result<Value> {
-    let auth_file = get_auth_file();
+    read_auth_in(&DataDir::resolve()).await
+}
+
+/// Read auth.json file from `dir`.
+pub async fn read_auth_in(dir: &DataDir) -> Result<Value> {
+    Ok(read_auth_with_passphrase_in(dir).await?.0)
+}
+
+/// Same as [`read_auth_in`], but also returns the passphrase used to
+/// decrypt the file (if it was encrypted), so a caller that immediately
+/// turns around and calls [`write_auth_in`] on the same value (remove,
+/// refresh) can pass it along instead of prompting for it a second time.
+async fn read_auth_with_passphrase_in(dir: &DataDir) -> Result<(Value, Option<String>)> {
+    let auth_file = get_auth_file(dir);
 
     if !auth_file.exists() {
-        return Ok(Value::Object(serde_json::Map::new()));
+        return Ok((Value::Object(serde_json::Map::new()), None));
+    }
+
+    let content = fs::read(&auth_file).await?;
+    if content.is_empty() {
+        return Ok((Value::Object(serde_json::Map::new()), None));
     }
 
-    let content = fs::read_to_string(&auth_file).await?;
-    let trimmed = content.trim();
+    let mut passphrase_used = None;
+    let json_bytes = if content.starts_with(CRYPT_MAGIC) {
+        let passphrase = auth_passphrase().await?;
+        let decrypted = {
+            let passphrase = passphrase.clone();
+            tokio::task::spawn_blocking(move || decrypt_auth(&content, &passphrase))
+                .await
+                .map_err(|e| anyhow!("Decrypt task panicked: {}", e))??
+        };
+        passphrase_used = Some(passphrase);
+        decrypted
+    } else {
+        content
+    };
+
+    let trimmed = std::str::from_utf8(&json_bytes)
+        .map_err(|e| anyhow!("Auth file is not valid UTF-8: {}", e))?
+        .trim();
 
     if trimmed.is_empty() {
-        return Ok(Value::Object(serde_json::Map::new()));
+        return Ok((Value::Object(serde_json::Map::new()), passphrase_used));
     }
 
-    serde_json::from_str(trimmed).map_err(|e| anyhow!("Failed to parse auth file: {}", e))
+    let value =
+        serde_json::from_str(trimmed).map_err(|e| anyhow!("Failed to parse auth file: {}", e))?;
+    Ok((value, passphrase_used))
 }
 
-/// Write auth.json file with backup
+/// Write auth.json file with backup to the resolved default data directory.
 pub async fn write_auth(auth: &Value) -> Result<()> {
-    ensure_data_dir().await?;
+    write_auth_in(&DataDir::resolve(), auth).await
+}
 
-    let auth_file = get_auth_file();
+/// Write auth.json file with backup to `dir`.
+pub async fn write_auth_in(dir: &DataDir, auth: &Value) -> Result<()> {
+    write_auth_with_passphrase_in(dir, auth, None).await
+}
 
-    // Create backup before writing
-    if auth_file.exists() {
-        let file_name = auth_file
-            .file_name()
-            .and_then(|name| name.to_str())
-            .ok_or_else(|| anyhow!("Invalid auth file name"))?;
+/// Same as [`write_auth_in`], but skips resolving a passphrase via
+/// `auth_passphrase()` (env var or TTY prompt) when `passphrase_override`
+/// is `Some` — used by callers that already derived it via
+/// [`read_auth_with_passphrase_in`] earlier in the same logical operation.
+async fn write_auth_with_passphrase_in(
+    dir: &DataDir,
+    auth: &Value,
+    passphrase_override: Option<&str>,
+) -> Result<()> {
+    ensure_data_dir(dir).await?;
+
+    let auth_file = get_auth_file(dir);
+
+    // An explicit CryptMode env var always wins, including an explicit
+    // opt-out (`none`/`decrypt`) that flips an already-encrypted auth.json
+    // back to plaintext. Absent that, an existing encrypted file stays
+    // encrypted on rewrite so a stale/unset env var can't silently
+    // downgrade it to plaintext.
+    let already_encrypted = file_is_encrypted(&auth_file).await?;
+    let crypt_mode = match CryptMode::current() {
+        Some(mode) => mode,
+        None if already_encrypted => CryptMode::Encrypt,
+        None => CryptMode::None,
+    };
+
+    let passphrase = match (crypt_mode, passphrase_override) {
+        (CryptMode::None, _) => None,
+        (CryptMode::Encrypt, Some(passphrase)) => Some(passphrase.to_string()),
+        (CryptMode::Encrypt, None) => Some(auth_passphrase().await?),
+    };
 
-        let backup_path = auth_file.with_file_name(format!("{file_name}.openchamber.backup"));
-        fs::copy(&auth_file, &backup_path).await?;
-        info!("Created auth backup: {}", backup_path.display());
+    // Create backup before writing. When this write is encrypted, the backup
+    // is encrypted too; otherwise flipping CryptMode::Encrypt on would
+    // encrypt auth.json but leave the still-plaintext backup of the
+    // previous write sitting right next to it. A backup of a file that is
+    // already encrypted is a verbatim copy, so it stays encrypted even when
+    // this write is plaintext.
+    if auth_file.exists() {
+        backup_auth_file(&auth_file, crypt_mode, already_encrypted, passphrase.as_deref()).await?;
     }
 
     let json_string = serde_json::to_string_pretty(auth)?;
-    fs::write(&auth_file, json_string).await?;
+
+    let bytes = match crypt_mode {
+        CryptMode::None => json_string.into_bytes(),
+        CryptMode::Encrypt => {
+            let passphrase =
+                passphrase.expect("passphrase is resolved above whenever crypt_mode is Encrypt");
+            tokio::task::spawn_blocking(move || encrypt_auth(json_string.as_bytes(), &passphrase))
+                .await
+                .map_err(|e| anyhow!("Encrypt task panicked: {}", e))??
+        }
+    };
+
+    write_auth_file_atomic(&auth_file, &bytes).await?;
     info!("Successfully wrote auth file");
 
     Ok(())
 }
 
-/// Remove provider auth entry from auth.json
+/// Create a sibling backup of `auth_file` before it's overwritten. If the
+/// write about to happen is encrypted but the on-disk file isn't yet,
+/// encrypt the backup too instead of copying it verbatim, so the backup
+/// never holds a plaintext copy of secrets the primary file now protects.
+async fn backup_auth_file(
+    auth_file: &std::path::Path,
+    crypt_mode: CryptMode,
+    already_encrypted: bool,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    let file_name = auth_file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid auth file name"))?;
+    let backup_path = auth_file.with_file_name(format!("{file_name}.openchamber.backup"));
+
+    if crypt_mode == CryptMode::Encrypt && !already_encrypted {
+        let content = fs::read(auth_file).await?;
+        let passphrase = passphrase
+            .ok_or_else(|| anyhow!("Passphrase required to encrypt auth backup"))?
+            .to_string();
+        let encrypted = tokio::task::spawn_blocking(move || encrypt_auth(&content, &passphrase))
+            .await
+            .map_err(|e| anyhow!("Encrypt task panicked: {}", e))??;
+        fs::write(&backup_path, encrypted).await?;
+    } else {
+        fs::copy(auth_file, &backup_path).await?;
+    }
+
+    info!("Created auth backup: {}", backup_path.display());
+    Ok(())
+}
+
+/// Write `bytes` to `path` via temp-file-and-rename, fsync'ing the temp
+/// file (and, on Unix, the parent directory) so a crash mid-write can
+/// never leave a partially-written auth file behind.
+async fn write_auth_file_atomic(path: &PathBuf, bytes: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid auth file name"))?;
+    // Suffix with a random value in addition to the pid so two concurrent
+    // writers in the same process (e.g. a background token refresh racing
+    // a user-triggered provider removal) never collide on the same
+    // `create_new` temp path.
+    let tmp_path = path.with_file_name(format!(
+        "{file_name}.tmp.{}.{:x}",
+        std::process::id(),
+        OsRng.next_u64()
+    ));
+
+    #[cfg(unix)]
+    {
+        // Drop any stale temp file from a previous crash so `create_new`
+        // below is guaranteed to apply the mode at creation time, not after.
+        let _ = fs::remove_file(&tmp_path).await;
+
+        let mut tmp_file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp_path)
+            .await?;
+        tmp_file.write_all(bytes).await?;
+        tmp_file.sync_all().await?;
+        drop(tmp_file);
+    }
+
+    #[cfg(not(unix))]
+    {
+        let mut tmp_file = fs::File::create(&tmp_path).await?;
+        tmp_file.write_all(bytes).await?;
+        tmp_file.sync_all().await?;
+        drop(tmp_file);
+    }
+
+    fs::rename(&tmp_path, path).await?;
+
+    // Opening a directory as a file handle (to fsync it) isn't portable:
+    // it requires FILE_FLAG_BACKUP_SEMANTICS on Windows, which this code
+    // doesn't set, so skip it there. POSIX filesystems support it directly.
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let dir = fs::File::open(parent).await?;
+        dir.sync_all().await?;
+    }
+
+    Ok(())
+}
+
+/// Remove provider auth entry from the resolved default auth.json.
 pub async fn remove_provider_auth(provider_id: &str) -> Result<bool> {
+    remove_provider_auth_in(&DataDir::resolve(), provider_id).await
+}
+
+/// Remove provider auth entry from the auth.json under `dir`.
+pub async fn remove_provider_auth_in(dir: &DataDir, provider_id: &str) -> Result<bool> {
     if provider_id.is_empty() {
         return Err(anyhow!("Provider ID is required"));
     }
 
-    let mut auth = read_auth().await?;
+    let (mut auth, passphrase) = read_auth_with_passphrase_in(dir).await?;
 
     let auth_obj = auth
         .as_object_mut()
@@ -89,10 +416,623 @@ pub async fn remove_provider_auth(provider_id: &str) -> Result<bool> {
     }
 
     auth_obj.remove(provider_id);
-    write_auth(&auth).await?;
+    write_auth_with_passphrase_in(dir, &auth, passphrase.as_deref()).await?;
     info!("Removed provider auth: {}", provider_id);
 
     Ok(true)
 }
 
+/// Default skew applied when checking token expiry.
+const DEFAULT_EXPIRY_SKEW_SECS: i64 = 60;
+
+/// Error returned by [`refresh_provider_auth`].
+#[derive(Debug)]
+pub enum RefreshError {
+    /// No `refresh` token is stored for this provider; force re-login.
+    NoRefreshToken,
+    /// The provider's token endpoint rejected the refresh token.
+    RefreshRejected(String),
+    /// Any other failure (network, parsing, storage).
+    Other(anyhow::Error),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::NoRefreshToken => {
+                write!(f, "No refresh token available; user must log in again")
+            }
+            RefreshError::RefreshRejected(reason) => {
+                write!(f, "Provider rejected the refresh token: {reason}")
+            }
+            RefreshError::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RefreshError {}
+
+impl From<anyhow::Error> for RefreshError {
+    fn from(e: anyhow::Error) -> Self {
+        RefreshError::Other(e)
+    }
+}
+
+/// Check whether the stored OAuth token for `provider_id` is expired.
+pub async fn is_token_expired(provider_id: &str) -> Result<bool> {
+    is_token_expired_with_skew(provider_id, DEFAULT_EXPIRY_SKEW_SECS).await
+}
+
+/// Same as [`is_token_expired`] with a caller-supplied skew.
+pub async fn is_token_expired_with_skew(provider_id: &str, skew_secs: i64) -> Result<bool> {
+    is_token_expired_in(&DataDir::resolve(), provider_id, skew_secs).await
+}
+
+/// Same as [`is_token_expired_with_skew`], scoped to the auth.json under `dir`.
+pub async fn is_token_expired_in(dir: &DataDir, provider_id: &str, skew_secs: i64) -> Result<bool> {
+    let auth = read_auth_in(dir).await?;
+
+    let entry = auth
+        .get(provider_id)
+        .ok_or_else(|| anyhow!("Provider {} not found in auth file", provider_id))?;
+
+    is_entry_expired(entry, skew_secs)
+}
+
+/// Epoch-millisecond timestamps below this predate September 2001; a real `expires`/
+/// `expires_at` this small is almost certainly epoch-seconds instead.
+const MIN_PLAUSIBLE_EPOCH_MS: i64 = 1_000_000_000_000;
+
+/// Shared expiry check used by [`is_token_expired_in`] and [`refresh_provider_auth`].
+///
+/// `expires`/`expires_at` is assumed to be epoch-milliseconds, matching
+/// what [`refresh_provider_auth_in`] writes back.
+fn is_entry_expired(entry: &Value, skew_secs: i64) -> Result<bool> {
+    let expires_at = entry
+        .get("expires")
+        .or_else(|| entry.get("expires_at"))
+        .and_then(Value::as_i64)
+        .ok_or_else(|| anyhow!("Provider entry has no expiry timestamp"))?;
+
+    if expires_at < MIN_PLAUSIBLE_EPOCH_MS {
+        return Err(anyhow!(
+            "Provider entry expiry timestamp {} looks like epoch-seconds, not epoch-milliseconds",
+            expires_at
+        ));
+    }
+
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis() as i64;
+
+    Ok(now_ms + skew_secs * 1000 >= expires_at)
+}
+
+/// If the OAuth token stored for `provider_id` is expired, refresh it
+/// against `token_endpoint` and write the updated tokens back via
+/// [`write_auth`]. Returns `Ok(())` without a network call if the token
+/// is still valid.
+pub async fn refresh_provider_auth(
+    provider_id: &str,
+    token_endpoint: &str,
+) -> std::result::Result<(), RefreshError> {
+    refresh_provider_auth_in(&DataDir::resolve(), provider_id, token_endpoint).await
+}
+
+/// Same as [`refresh_provider_auth`], scoped to the auth.json under `dir`.
+pub async fn refresh_provider_auth_in(
+    dir: &DataDir,
+    provider_id: &str,
+    token_endpoint: &str,
+) -> std::result::Result<(), RefreshError> {
+    let (mut auth, passphrase) = read_auth_with_passphrase_in(dir).await?;
+
+    let auth_obj = auth
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Auth file is not a valid JSON object"))?;
+
+    let entry = auth_obj
+        .get(provider_id)
+        .ok_or_else(|| anyhow!("Provider {} not found in auth file", provider_id))?;
+
+    if !is_entry_expired(entry, DEFAULT_EXPIRY_SKEW_SECS)? {
+        info!(
+            "Token for provider {} is still valid; skipping refresh",
+            provider_id
+        );
+        return Ok(());
+    }
+
+    let refresh_token = entry
+        .get("refresh")
+        .and_then(Value::as_str)
+        .ok_or(RefreshError::NoRefreshToken)?
+        .to_string();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(token_endpoint)
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+        ])
+        .send()
+        .await
+        .map_err(|e| RefreshError::Other(anyhow!("Failed to reach token endpoint: {}", e)))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(RefreshError::RefreshRejected(format!(
+            "{status}: {body}"
+        )));
+    }
+
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| RefreshError::Other(anyhow!("Failed to parse refresh response: {}", e)))?;
+
+    let access = body
+        .get("access_token")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("Refresh response is missing access_token"))?;
+    let new_refresh = body
+        .get("refresh_token")
+        .and_then(Value::as_str)
+        .unwrap_or(&refresh_token);
+    let expires_in_secs = body
+        .get("expires_in")
+        .and_then(Value::as_i64)
+        .ok_or_else(|| anyhow!("Refresh response is missing expires_in"))?;
+
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| anyhow!("System clock error: {}", e))?
+        .as_millis() as i64;
+    let expires_at = now_ms + expires_in_secs * 1000;
+
+    let auth_obj = auth
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Auth file is not a valid JSON object"))?;
+    if let Some(entry) = auth_obj.get_mut(provider_id).and_then(Value::as_object_mut) {
+        entry.insert("access".to_string(), Value::String(access.to_string()));
+        entry.insert("refresh".to_string(), Value::String(new_refresh.to_string()));
+        entry.insert("expires".to_string(), Value::from(expires_at));
+    }
+
+    write_auth_with_passphrase_in(dir, &auth, passphrase.as_deref()).await?;
+    info!("Refreshed OAuth token for provider: {}", provider_id);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn now_ms() -> i64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64
+    }
+
+    /// `cargo test` runs unit tests on multiple OS threads by default, but
+    /// `OPENCHAMBER_CRYPT_MODE`/`OPENCHAMBER_AUTH_PASSPHRASE` are process-wide
+    /// state. Hold this for the duration of any test that sets them, so it
+    /// can't flip crypt-mode out from under an unrelated test's `write_auth_in`
+    /// call running on another thread.
+    static ENV_MUTEX: Mutex<()> = Mutex::new(());
+
+    /// RAII guard that sets an env var for the duration of a test and
+    /// restores the prior value (or absence) on drop, so a panicked
+    /// assertion can't leak the override into tests that run after it.
+    struct EnvVarGuard {
+        key: &'static str,
+        previous: Option<String>,
+    }
+
+    impl EnvVarGuard {
+        fn set(key: &'static str, value: &str) -> Self {
+            let previous = std::env::var(key).ok();
+            unsafe { std::env::set_var(key, value) };
+            Self { key, previous }
+        }
+    }
+
+    impl Drop for EnvVarGuard {
+        fn drop(&mut self) {
+            match &self.previous {
+                Some(value) => unsafe { std::env::set_var(self.key, value) },
+                None => unsafe { std::env::remove_var(self.key) },
+            }
+        }
+    }
+
+    #[test]
+    fn encrypt_decrypt_round_trip() {
+        let plaintext = br#"{"anthropic":{"access":"abc"}}"#;
+        let passphrase = "correct horse battery staple";
+
+        let container = encrypt_auth(plaintext, passphrase).unwrap();
+        let decrypted = decrypt_auth(&container, passphrase).unwrap();
+
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn decrypt_with_wrong_passphrase_is_distinct_from_parse_error() {
+        let plaintext = br#"{"anthropic":{"access":"abc"}}"#;
+        let container = encrypt_auth(plaintext, "right-passphrase").unwrap();
+
+        let err = decrypt_auth(&container, "wrong-passphrase").unwrap_err();
+
+        assert_eq!(err.to_string(), "Wrong passphrase or tampered auth file");
+    }
+
+    #[test]
+    fn decrypt_tampered_ciphertext_is_distinct_from_parse_error() {
+        let plaintext = br#"{"anthropic":{"access":"abc"}}"#;
+        let passphrase = "correct horse battery staple";
+        let mut container = encrypt_auth(plaintext, passphrase).unwrap();
+        let last = container.len() - 1;
+        container[last] ^= 0xFF;
+
+        let err = decrypt_auth(&container, passphrase).unwrap_err();
+
+        assert_eq!(err.to_string(), "Wrong passphrase or tampered auth file");
+    }
+
+    #[test]
+    fn entry_outside_skew_window_is_not_expired() {
+        let entry = serde_json::json!({ "expires": now_ms() + 120_000 });
+
+        assert!(!is_entry_expired(&entry, 60).unwrap());
+    }
+
+    #[test]
+    fn entry_inside_skew_window_is_expired() {
+        let entry = serde_json::json!({ "expires": now_ms() + 30_000 });
+
+        assert!(is_entry_expired(&entry, 60).unwrap());
+    }
+
+    #[test]
+    fn entry_past_expiry_is_expired() {
+        let entry = serde_json::json!({ "expires": now_ms() - 1 });
+
+        assert!(is_entry_expired(&entry, 0).unwrap());
+    }
+
+    #[test]
+    fn entry_with_seconds_scale_timestamp_is_rejected() {
+        let entry = serde_json::json!({ "expires": now_ms() / 1000 });
+
+        let err = is_entry_expired(&entry, 60).unwrap_err();
+
+        assert!(err.to_string().contains("epoch-seconds"));
+    }
+
+    #[tokio::test]
+    async fn write_read_remove_round_trip_in_scratch_dir() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-auth-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let auth = serde_json::json!({ "anthropic": { "access": "abc" } });
+        write_auth_in(&dir, &auth).await.unwrap();
+
+        let read_back = read_auth_in(&dir).await.unwrap();
+        assert_eq!(read_back, auth);
+
+        let removed = remove_provider_auth_in(&dir, "anthropic").await.unwrap();
+        assert!(removed);
+
+        let after_remove = read_auth_in(&dir).await.unwrap();
+        assert_eq!(after_remove, serde_json::json!({}));
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn write_preserves_encryption_even_when_crypt_mode_env_is_unset() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let passphrase = "correct horse battery staple";
+        let _passphrase_guard = EnvVarGuard::set("OPENCHAMBER_AUTH_PASSPHRASE", passphrase);
+
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-crypt-preserve-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+        fs::create_dir_all(dir.path()).await.unwrap();
+        let auth_file = get_auth_file(&dir);
+
+        let initial = serde_json::json!({ "anthropic": { "access": "abc" } });
+        let container = encrypt_auth(
+            serde_json::to_string_pretty(&initial).unwrap().as_bytes(),
+            passphrase,
+        )
+        .unwrap();
+        fs::write(&auth_file, &container).await.unwrap();
+
+        // OPENCHAMBER_CRYPT_MODE/OPENCODE_CRYPT_MODE are unset here, so
+        // CryptMode::current() returns None, which on its own would mean a
+        // plaintext write; write_auth_in
+        // must still re-encrypt because the file it's replacing already is.
+        let mut updated = read_auth_in(&dir).await.unwrap();
+        updated["anthropic"]["access"] = serde_json::json!("rotated");
+        write_auth_in(&dir, &updated).await.unwrap();
+
+        let raw = fs::read(&auth_file).await.unwrap();
+        assert!(raw.starts_with(CRYPT_MAGIC));
+
+        let round_tripped = read_auth_in(&dir).await.unwrap();
+        assert_eq!(round_tripped, updated);
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn backup_is_encrypted_when_flipping_crypt_mode_on() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let passphrase = "correct horse battery staple";
+
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-backup-encrypt-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+        let auth_file = get_auth_file(&dir);
+
+        // First write is plaintext (CryptMode::None, the default).
+        let initial = serde_json::json!({ "anthropic": { "access": "abc" } });
+        write_auth_in(&dir, &initial).await.unwrap();
+
+        // Flip CryptMode on and write again; the previous plaintext content
+        // is now backed up, and the backup must not stay in cleartext.
+        let _passphrase_guard = EnvVarGuard::set("OPENCHAMBER_AUTH_PASSPHRASE", passphrase);
+        let _crypt_mode_guard = EnvVarGuard::set("OPENCHAMBER_CRYPT_MODE", "encrypt");
+
+        let updated = serde_json::json!({ "anthropic": { "access": "rotated" } });
+        write_auth_in(&dir, &updated).await.unwrap();
+
+        let backup_path = auth_file.with_file_name(format!(
+            "{}.openchamber.backup",
+            auth_file.file_name().unwrap().to_str().unwrap()
+        ));
+        let backup_raw = fs::read(&backup_path).await.unwrap();
+        assert!(backup_raw.starts_with(CRYPT_MAGIC));
+
+        let decrypted = decrypt_auth(&backup_raw, passphrase).unwrap();
+        let backup_value: serde_json::Value = serde_json::from_slice(&decrypted).unwrap();
+        assert_eq!(backup_value, initial);
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_do_not_collide_on_tmp_path() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-concurrent-write-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let writers = (0..20).map(|i| {
+            let dir = dir.clone();
+            tokio::spawn(async move {
+                let auth = serde_json::json!({ "anthropic": { "access": format!("token-{i}") } });
+                write_auth_in(&dir, &auth).await
+            })
+        });
+
+        for writer in writers {
+            writer.await.unwrap().unwrap();
+        }
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn is_token_expired_in_reflects_scratch_dir_entry() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-expiry-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let auth = serde_json::json!({
+            "anthropic": { "access": "abc", "expires": now_ms() - 1 }
+        });
+        write_auth_in(&dir, &auth).await.unwrap();
+
+        assert!(is_token_expired_in(&dir, "anthropic", 0).await.unwrap());
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn refresh_provider_auth_skips_network_call_when_token_is_valid() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-refresh-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let auth = serde_json::json!({
+            "anthropic": {
+                "access": "still-valid-access-token",
+                "refresh": "still-valid-refresh-token",
+                "expires": now_ms() + 3_600_000,
+            }
+        });
+        write_auth_in(&dir, &auth).await.unwrap();
+
+        // Port 0 is never a reachable endpoint; if `refresh_provider_auth_in`
+        // attempted a request despite the token being valid, this would
+        // fail with a connection error instead of returning `Ok(())`.
+        refresh_provider_auth_in(&dir, "anthropic", "http://127.0.0.1:0")
+            .await
+            .unwrap();
+
+        let unchanged = read_auth_in(&dir).await.unwrap();
+        assert_eq!(unchanged, auth);
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    /// Spawn a one-shot mock HTTP endpoint on a background thread, returning
+    /// its `http://` URL. Replies once with `status_line` and `body`, then
+    /// the listener thread exits.
+    fn spawn_mock_token_endpoint(status_line: &'static str, body: String) -> String {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf);
+
+                let response = format!(
+                    "HTTP/1.1 {status_line}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn refresh_provider_auth_rewrites_tokens_on_success() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-refresh-success-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let auth = serde_json::json!({
+            "anthropic": {
+                "access": "expired-access-token",
+                "refresh": "old-refresh-token",
+                "expires": now_ms() - 1,
+                "scope": "read",
+            }
+        });
+        write_auth_in(&dir, &auth).await.unwrap();
+
+        let endpoint = spawn_mock_token_endpoint(
+            "200 OK",
+            serde_json::json!({
+                "access_token": "new-access-token",
+                "refresh_token": "new-refresh-token",
+                "expires_in": 3600,
+            })
+            .to_string(),
+        );
+
+        refresh_provider_auth_in(&dir, "anthropic", &endpoint)
+            .await
+            .unwrap();
+
+        let updated = read_auth_in(&dir).await.unwrap();
+        let entry = &updated["anthropic"];
+        assert_eq!(entry["access"], "new-access-token");
+        assert_eq!(entry["refresh"], "new-refresh-token");
+        assert_eq!(entry["scope"], "read");
+        assert!(entry["expires"].as_i64().unwrap() > now_ms());
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn refresh_provider_auth_without_refresh_token_is_no_refresh_token_error() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-refresh-norefresh-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let auth = serde_json::json!({
+            "anthropic": {
+                "access": "expired-access-token",
+                "expires": now_ms() - 1,
+            }
+        });
+        write_auth_in(&dir, &auth).await.unwrap();
+
+        // Port 0 is never reachable; NoRefreshToken must be returned before
+        // any request is attempted.
+        let err = refresh_provider_auth_in(&dir, "anthropic", "http://127.0.0.1:0")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, RefreshError::NoRefreshToken));
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+
+    #[tokio::test]
+    async fn refresh_provider_auth_rejected_response_is_refresh_rejected_error() {
+        let _lock = ENV_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        let scratch = std::env::temp_dir().join(format!(
+            "openchamber-refresh-rejected-test-{}-{}",
+            std::process::id(),
+            now_ms()
+        ));
+        let dir = DataDir::from_path(&scratch);
+
+        let auth = serde_json::json!({
+            "anthropic": {
+                "access": "expired-access-token",
+                "refresh": "revoked-refresh-token",
+                "expires": now_ms() - 1,
+            }
+        });
+        write_auth_in(&dir, &auth).await.unwrap();
+
+        let endpoint =
+            spawn_mock_token_endpoint("401 Unauthorized", "invalid_grant".to_string());
+
+        let err = refresh_provider_auth_in(&dir, "anthropic", &endpoint)
+            .await
+            .unwrap_err();
+
+        match err {
+            RefreshError::RefreshRejected(message) => {
+                assert!(message.contains("invalid_grant"));
+            }
+            other => panic!("expected RefreshRejected, got {other:?}"),
+        }
+
+        let unchanged = read_auth_in(&dir).await.unwrap();
+        assert_eq!(unchanged, auth);
+
+        let _ = fs::remove_dir_all(&scratch).await;
+    }
+}
+
 